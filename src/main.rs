@@ -1,11 +1,11 @@
 mod webrequest;
 
-use std::cmp::{max, min};
 use std::collections::HashMap;
-use std::ops::{Add, Div};
 use std::process::Stdio;
+use std::sync::Arc;
 use std::time::Duration;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use clap::error::ErrorKind;
 use tokio::process::Command;
 use tokio::sync::mpsc;
 use tokio::time::{Instant, sleep};
@@ -31,36 +31,184 @@ struct Args {
     #[arg(long = "use-builtin", default_value_t = false, help = "curlコマンドのかわりに組み込みのWebリクエスト機能を使用します。いくつかのcurlオプションは使えません。")]
     builtin: bool,
 
+    #[arg(long, help = "1秒あたりのリクエスト数を指定し、オープンループ(一定レート)でリクエストを発行します。レスポンスを待たずスケジュール通りに発行するため、同時実行数が上限に達するまでは遅延が悪化してもスループットは落ちません。指定時は--parallelが同時実行数の上限として扱われ、上限に達したリクエストは空きが出るまで待機します。")]
+    rate: Option<f64>,
+
     #[arg(last = true, help = "cURL引数")]
     curl_args: Vec<String>,
 }
 
+// `time_appconnect`系はTLSを使わない通信では0になるため、各フェーズは
+// 計測できた場合のみ埋める。curlには協商されたTLSバージョンを取得する
+// `-w`変数が存在しないため(`%{ssl_version}`は未知の変数としてエラーになる)、
+// tls_versionはcurl経路でも常にNoneになる(builtin経路と同じ制約)。
+#[allow(dead_code)]
+#[derive(Clone, Default, Debug)]
+pub struct PhaseTimings {
+    dns: Option<Duration>,
+    connect: Option<Duration>,
+    tls: Option<Duration>,
+    ttfb: Option<Duration>,
+    total: Option<Duration>,
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 struct Response {
     time: Duration,
+    phase: Option<PhaseTimings>,
     status_code: String,
+    http_version: Option<String>,
+    tls_version: Option<String>,
     exit_status: i32,
     error: String,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Default)]
+struct PhaseStat {
+    mean_us: u64,
+    min_us: u64,
+    max_us: u64,
+    p50_us: u64,
+    p90_us: u64,
+    p95_us: u64,
+    p99_us: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct PhaseMetrics {
+    dns: Option<PhaseStat>,
+    connect: Option<PhaseStat>,
+    tls: Option<PhaseStat>,
+    ttfb: Option<PhaseStat>,
+    total: Option<PhaseStat>,
+}
+
+#[derive(Serialize, Debug, Default)]
 struct Metrics {
-    mean_time: u32,
-    max_time: u32,
-    min_time: u32,
-    variance_time: u32,
-    quartile_25: u32,
-    quartile_75: u32,
-    
+    total_requests: usize,
+    requests_per_sec: f64,
+
+    mean_time_us: u64,
+    min_time_us: u64,
+    max_time_us: u64,
+    variance_time_us: f64,
+    stddev_time_us: f64,
+    p50_time_us: u64,
+    p90_time_us: u64,
+    p95_time_us: u64,
+    p99_time_us: u64,
+    p999_time_us: u64,
+
+    phase_metrics: PhaseMetrics,
+
     status_count: HashMap<String, usize>,
+    http_version_count: HashMap<String, usize>,
+    tls_version_count: HashMap<String, usize>,
     error_count: usize,
 }
 
-async fn call_curl(args: &[String]) -> Response {
-    debug!("{:?}", args);
+fn count_by<F: Fn(&Response) -> Option<String>>(response: &[Response], f: F) -> HashMap<String, usize> {
+    response
+      .iter()
+      .filter_map(f)
+      .fold(HashMap::new(), |mut acc, key| {
+          if acc.contains_key(&key) {
+              acc.insert(key.clone(), acc[&key] + 1);
+          } else {
+              acc.insert(key.clone(), 1);
+          }
+          acc
+      })
+}
+
+// nearest-rank法でパーセンタイルを求める。`sorted`は昇順でソート済みであること。
+// 小さいサンプル数でも範囲外アクセスにならないようインデックスをクランプする。
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let n = sorted.len();
+    let idx = ((p / 100.0) * n as f64).ceil() as usize;
+    let idx = idx.saturating_sub(1).min(n - 1);
+    sorted[idx]
+}
+
+fn phase_stat(samples: Vec<u64>) -> PhaseStat {
+    let mut sorted = samples;
+    sorted.sort();
+
+    let mean_us = (sorted.iter().sum::<u64>() as f64 / sorted.len() as f64).round() as u64;
+
+    PhaseStat {
+        mean_us,
+        min_us: sorted[0],
+        max_us: sorted[sorted.len() - 1],
+        p50_us: percentile(&sorted, 50.0),
+        p90_us: percentile(&sorted, 90.0),
+        p95_us: percentile(&sorted, 95.0),
+        p99_us: percentile(&sorted, 99.0),
+    }
+}
 
-    let now = Instant::now();
+fn phase_metrics(response: &[Response]) -> PhaseMetrics {
+    let dns = response.iter().filter_map(|x| x.phase.as_ref()?.dns).map(|d| d.as_micros() as u64).collect::<Vec<_>>();
+    let connect = response.iter().filter_map(|x| x.phase.as_ref()?.connect).map(|d| d.as_micros() as u64).collect::<Vec<_>>();
+    let tls = response.iter().filter_map(|x| x.phase.as_ref()?.tls).map(|d| d.as_micros() as u64).collect::<Vec<_>>();
+    let ttfb = response.iter().filter_map(|x| x.phase.as_ref()?.ttfb).map(|d| d.as_micros() as u64).collect::<Vec<_>>();
+    let total = response.iter().filter_map(|x| x.phase.as_ref()?.total).map(|d| d.as_micros() as u64).collect::<Vec<_>>();
+
+    PhaseMetrics {
+        dns: if dns.is_empty() { None } else { Some(phase_stat(dns)) },
+        connect: if connect.is_empty() { None } else { Some(phase_stat(connect)) },
+        tls: if tls.is_empty() { None } else { Some(phase_stat(tls)) },
+        ttfb: if ttfb.is_empty() { None } else { Some(phase_stat(ttfb)) },
+        total: if total.is_empty() { None } else { Some(phase_stat(total)) },
+    }
+}
+
+// `-w`テンプレートで埋め込んだ `%{http_code} %{time_namelookup} %{time_connect}
+// %{time_appconnect} %{time_starttransfer} %{time_total} %{http_version}`
+// を空白区切りで読み取る。TLSを使わない場合は`time_appconnect`が0のままなのでtls
+// フェーズはNoneにする。
+// curlの`%{http_version}`は"1.0"/"1.1"の区別をせず常に"1"を返す
+// (`--http1.0`/`--http1.1`どちらを指定しても同じ)ため、builtin経路の
+// `http_version_label`もこれに合わせて"1"に丸め、両経路でhttp_version_countの
+// キーが一致するようにしている。
+fn parse_curl_timing(stdout: &str) -> (String, Option<PhaseTimings>, Option<String>) {
+    let field = stdout.split_whitespace().collect::<Vec<_>>();
+
+    let status_code = match field.first() {
+        Some(status_code) => status_code.to_string(),
+        None => return ("client error".to_string(), None, None),
+    };
+
+    let seconds = |index: usize| field.get(index).and_then(|x| x.parse::<f64>().ok());
+
+    let phase = match (seconds(1), seconds(2), seconds(3), seconds(4)) {
+        (Some(namelookup), Some(connect), Some(appconnect), Some(starttransfer)) => {
+            let total = seconds(5).unwrap_or(starttransfer);
+            Some(PhaseTimings {
+                dns: Some(Duration::from_secs_f64(namelookup)),
+                connect: Some(Duration::from_secs_f64((connect - namelookup).max(0.0))),
+                tls: if appconnect > 0.0 { Some(Duration::from_secs_f64((appconnect - connect).max(0.0))) } else { None },
+                ttfb: Some(Duration::from_secs_f64((starttransfer - appconnect.max(connect)).max(0.0))),
+                total: Some(Duration::from_secs_f64(total)),
+            })
+        }
+        _ => None,
+    };
+
+    let http_version = field.get(6).map(|x| x.to_string());
+
+    (status_code, phase, http_version)
+}
+
+// `start`は通常は呼び出し直前のInstant::now()だが、--rateのオープンループでは
+// スケジュールされた発行予定時刻を渡す。これにより実測の遅延にキューイング
+// 待ちの時間も含まれ、飽和時に遅延が隠れない。
+async fn call_curl(args: &[String], start: Instant) -> Response {
+    debug!("{:?}", args);
 
     let output = Command::new("curl")
       .args(args)
@@ -69,116 +217,93 @@ async fn call_curl(args: &[String]) -> Response {
       .await
       .unwrap();
 
-    let status_code = if output.status.code().unwrap() != 0 {
-        "client error".to_string()
+    let (status_code, phase, http_version) = if output.status.code().unwrap() != 0 {
+        ("client error".to_string(), None, None)
     } else {
-        String::from_utf8_lossy(&output.stdout).parse().unwrap()
+        parse_curl_timing(&String::from_utf8_lossy(&output.stdout))
     };
 
-    let delta = now.elapsed();
+    let delta = start.elapsed();
 
     debug!("{:?}", output);
 
     Response {
         time: delta,
+        phase,
         status_code,
+        http_version,
+        // curlには協商されたTLSバージョンを取得する`-w`変数が存在しないため常にNone。
+        tls_version: None,
         exit_status: output.status.code().unwrap(),
         error: String::from_utf8_lossy(&output.stderr).parse().unwrap(),
     }
 }
 
-async fn call_builtin(args: &[String]) -> Response {
-    debug!("{:?}", args);
-
-    let now = Instant::now();
-
+async fn call_builtin(client: &WebClient, start: Instant) -> Response {
     let mut exit_status = 0;
     let mut status_code = "client error".to_string();
     let mut error_msg = "".to_string();
-    let mut output = None;
-
-    let client = WebClient::build(args);
-    if client.is_ok() {
-        let response = client.unwrap().send().await;
-        if response.is_ok() {
-            output = Some(response.unwrap());
-            if let Some(ref output) = output {
-                status_code = output.status().to_string();
-            }
-        } else {
+    let mut phase = None;
+    let mut http_version = None;
+
+    match client.send().await {
+        Ok((response, timings)) => {
+            phase = Some(timings);
+            status_code = response.status_code;
+            http_version = Some(response.http_version);
+        }
+        Err(e) => {
             exit_status = 1;
-            error_msg = response.unwrap_err().to_string();
+            error_msg = e.to_string();
         }
-    } else {
-        exit_status = 1;
-        error_msg = client.unwrap_err().to_string();
     }
 
-    let delta = now.elapsed();
-
-    if let Some(output) = output {
-        debug!("{:?}", output);
-    }
+    let delta = start.elapsed();
 
     Response {
         time: delta,
+        phase,
         status_code,
+        http_version,
+        // reqwestは協商されたTLSバージョンを取得する公開APIを持たないため、
+        // builtin経路ではtls_versionは常にNoneになる(curl経路もNone、上記参照)。
+        tls_version: None,
         exit_status,
         error: error_msg,
     }
 }
 
-fn statistics(response: &[Response]) -> Metrics {
-    let time = response
-      .into_iter()
-      .map(|x| x.time.subsec_millis());
+// `elapsed`はワーカー単位ではなく実行全体の壁時計時間。これをもとにスループットを出す。
+// `--repeat 0`等でresponseが空になりうるため、その場合は0除算/範囲外アクセスを
+// 避けてゼロ値のMetricsを返す。
+fn statistics(response: &[Response], elapsed: Duration) -> Metrics {
+    if response.is_empty() {
+        return Metrics::default();
+    }
 
-    let mean_time = time
-      .clone()
-      .reduce(|acc, x| acc.add(x))
-      .unwrap()
-      .div(response.len() as u32);
+    let mut sorted_time_us = response
+      .iter()
+      .map(|x| x.time.as_micros() as u64)
+      .collect::<Vec<_>>();
+    sorted_time_us.sort();
 
-    let max_time = time
-      .clone()
-      .reduce(|a, b| max(a, b))
-      .unwrap();
+    let n = sorted_time_us.len();
 
-    let min_time = time
-      .clone()
-      .reduce(|a, b| min(a, b))
-      .unwrap();
+    let mean_time_us = sorted_time_us.iter().sum::<u64>() as f64 / n as f64;
 
-    let variance_time = time
-      .clone()
-      .map(|x| {
-          // (&mean_time).sub(x).pow(2)
-          (&mean_time).abs_diff(x)
+    let variance_time_us = sorted_time_us
+      .iter()
+      .map(|&x| {
+          let diff = x as f64 - mean_time_us;
+          diff * diff
       })
-      .reduce(|acc, x| acc + x)
-      .unwrap() / response.len() as u32;
-
-    let mut quartile = time
-      .clone()
-      .collect::<Vec<_>>();
-    quartile.sort();
-
-    let quartile_25 = quartile[response.len() * 1 / 4];
+      .sum::<f64>() / n as f64;
+    let stddev_time_us = variance_time_us.sqrt();
 
-    let quartile_75 = quartile[response.len() * 3 / 4];
+    let status_count = count_by(response, |x| Some(x.status_code.clone()));
+    let http_version_count = count_by(response, |x| x.http_version.clone());
+    let tls_version_count = count_by(response, |x| x.tls_version.clone());
 
-    let status_count = response
-      .iter()
-      .map(|x| x.status_code.clone())
-      .fold(HashMap::new(), |mut acc, status_code| {
-          if acc.contains_key(&status_code) {
-              acc.insert(status_code.clone(), acc[&status_code] + 1);
-          } else {
-              acc.insert(status_code.clone(), 1);
-          }
-          acc
-      });
-    
     let error_count = response
       .iter()
       .map(|x| x.exit_status.clone())
@@ -187,18 +312,41 @@ fn statistics(response: &[Response]) -> Metrics {
       .len();
 
     Metrics {
-        mean_time,
-        max_time,
-        min_time,
-        variance_time,
-        quartile_25,
-        quartile_75,
+        total_requests: n,
+        requests_per_sec: n as f64 / elapsed.as_secs_f64(),
+
+        mean_time_us: mean_time_us.round() as u64,
+        min_time_us: sorted_time_us[0],
+        max_time_us: sorted_time_us[n - 1],
+        variance_time_us,
+        stddev_time_us,
+        p50_time_us: percentile(&sorted_time_us, 50.0),
+        p90_time_us: percentile(&sorted_time_us, 90.0),
+        p95_time_us: percentile(&sorted_time_us, 95.0),
+        p99_time_us: percentile(&sorted_time_us, 99.0),
+        p999_time_us: percentile(&sorted_time_us, 99.9),
+
+        phase_metrics: phase_metrics(response),
 
         status_count,
+        http_version_count,
+        tls_version_count,
         error_count,
     }
 }
 
+// `tokio::time::interval(Duration::from_secs_f64(1.0 / rate))`はrateが0以下や
+// NaNだとpanicするため、オープンループに入る前にここで弾く。
+fn validate_rate(rate: Option<f64>) -> Result<(), clap::Error> {
+    match rate {
+        Some(rate) if !(rate > 0.0) => Err(Args::command().error(
+            ErrorKind::InvalidValue,
+            format!("error: invalid value '{rate}' for '--rate <RATE>': rate must be greater than 0")
+        )),
+        _ => Ok(()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
@@ -206,6 +354,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
     debug!("{:?}", args);
 
+    validate_rate(args.rate)?;
+
     let mut curl_args = args.curl_args.clone();
     if args.builtin {
         curl_args.insert(0, "curl".to_string());
@@ -219,17 +369,77 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         if !args.curl_args.contains(&String::from("-w")) {
             curl_args.push("-w".to_string());
-            curl_args.push("%{http_code}".to_string());
+            curl_args.push("%{http_code} %{time_namelookup} %{time_connect} %{time_appconnect} %{time_starttransfer} %{time_total} %{http_version}".to_string());
         }
     }
 
+    // cookieを使ったセッションを実行全体で維持するため、Clientはループの外で
+    // 一度だけ組み立てて全ワーカーで共有する。
+    let web_client = if args.builtin {
+        Some(Arc::new(WebClient::build(&curl_args)?))
+    } else {
+        None
+    };
+
+    let run_start = Instant::now();
+
     let mut handle = Vec::new();
 
     let (tx, mut rx) = mpsc::channel(1024);
 
-    if args.parallel > 0 {
+    if let Some(rate) = args.rate {
+        // オープンループ: 完了を待たずtokio::time::intervalのスケジュール通りに
+        // リクエストを発行する。同時実行数は--parallelを上限とするセマフォで
+        // 制御し、上限に達した分はパーミット待ちになる。レイテンシはタスク開始
+        // 時刻ではなく、このスケジュール上のtick時刻から測ることでキューイング
+        // 遅延を計測結果に含める。
+        // `--parallel 0`は他のモードと同じく「無制限」を表すが、Semaphore::newは
+        // Semaphore::MAX_PERMITSを超えるpermits数に対してpanicするのでそこで丸める。
+        let cap = if args.parallel > 0 { args.parallel } else { tokio::sync::Semaphore::MAX_PERMITS };
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(cap));
+        let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / rate));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Burst);
+
+        if let Some(time) = args.time {
+            let deadline = Instant::now() + Duration::from_secs(time.try_into().unwrap());
+            while Instant::now() < deadline {
+                let scheduled = ticker.tick().await;
+                let curl_args = curl_args.clone();
+                let web_client = web_client.clone();
+                let tx = tx.clone();
+                let semaphore = semaphore.clone();
+                handle.push(tokio::spawn(async move {
+                    let _permit = semaphore.acquire_owned().await.unwrap();
+                    let response = if args.builtin {
+                        call_builtin(web_client.as_ref().unwrap(), scheduled).await
+                    } else {
+                        call_curl(&curl_args, scheduled).await
+                    };
+                    if let Err(_) = tx.send(response).await { warn!("receiver dropped") }
+                }));
+            }
+        } else {
+            for _repeat in 0..args.repeat {
+                let scheduled = ticker.tick().await;
+                let curl_args = curl_args.clone();
+                let web_client = web_client.clone();
+                let tx = tx.clone();
+                let semaphore = semaphore.clone();
+                handle.push(tokio::spawn(async move {
+                    let _permit = semaphore.acquire_owned().await.unwrap();
+                    let response = if args.builtin {
+                        call_builtin(web_client.as_ref().unwrap(), scheduled).await
+                    } else {
+                        call_curl(&curl_args, scheduled).await
+                    };
+                    if let Err(_) = tx.send(response).await { warn!("receiver dropped") }
+                }));
+            }
+        }
+    } else if args.parallel > 0 {
         for _parallels in 0..args.parallel {
             let curl_args = curl_args.clone();
+            let web_client = web_client.clone();
             let tx = tx.clone();
             handle.push(if args.time.is_some() {
                 let now = Instant::now();
@@ -237,13 +447,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 tokio::spawn(async move {
                     if args.builtin {
                         while now.elapsed() < Duration::from_secs(time.try_into().unwrap()) {
-                            let response = call_builtin(&curl_args).await;
+                            let response = call_builtin(web_client.as_ref().unwrap(), Instant::now()).await;
                             if let Err(_) = tx.send(response).await { warn!("receiver dropped") }
                             sleep(Duration::from_millis(args.wait)).await;
                         }
                     } else {
                         while now.elapsed() < Duration::from_secs(time.try_into().unwrap()) {
-                            let response = call_curl(&curl_args).await;
+                            let response = call_curl(&curl_args, Instant::now()).await;
                             if let Err(_) = tx.send(response).await { warn!("receiver dropped") }
                             sleep(Duration::from_millis(args.wait)).await;
                         }
@@ -253,13 +463,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 tokio::spawn(async move {
                     if args.builtin {
                         for _repeat in 0..args.repeat {
-                            let response = call_builtin(&curl_args).await;
+                            let response = call_builtin(web_client.as_ref().unwrap(), Instant::now()).await;
                             if let Err(_) = tx.send(response).await { warn!("receiver dropped") }
                             sleep(Duration::from_millis(args.wait)).await;
                         }
                     } else {
                         for _repeat in 0..args.repeat {
-                            let response = call_curl(&curl_args).await;
+                            let response = call_curl(&curl_args, Instant::now()).await;
                             if let Err(_) = tx.send(response).await { warn!("receiver dropped") }
                             sleep(Duration::from_millis(args.wait)).await;
                         }
@@ -275,14 +485,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let mut inner_handle = Vec::new();
                 while now.elapsed() < Duration::from_secs(time.try_into().unwrap()) {
                     let curl_args = curl_args.clone();
+                    let web_client = web_client.clone();
                     let tx = tx.clone();
                     inner_handle.push(tokio::spawn(async move {
                         if args.builtin {
-                            let response = call_builtin(&curl_args).await;
+                            let response = call_builtin(web_client.as_ref().unwrap(), Instant::now()).await;
                             if let Err(_) = tx.send(response).await { warn!("receiver dropped") }
                             sleep(Duration::from_millis(args.wait)).await;
                         } else {
-                            let response = call_curl(&curl_args).await;
+                            let response = call_curl(&curl_args, Instant::now()).await;
                             if let Err(_) = tx.send(response).await { warn!("receiver dropped") }
                             sleep(Duration::from_millis(args.wait)).await;
                         }
@@ -293,14 +504,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let mut inner_handle = Vec::new();
                 for _repeat in 0..args.repeat {
                     let curl_args = curl_args.clone();
+                    let web_client = web_client.clone();
                     let tx = tx.clone();
                     inner_handle.push(tokio::spawn(async move {
                         if args.builtin {
-                            let response = call_builtin(&curl_args).await;
+                            let response = call_builtin(web_client.as_ref().unwrap(), Instant::now()).await;
                             if let Err(_) = tx.send(response).await { warn!("receiver dropped") }
                             sleep(Duration::from_millis(args.wait)).await;
                         } else {
-                            let response = call_curl(&curl_args).await;
+                            let response = call_curl(&curl_args, Instant::now()).await;
                             if let Err(_) = tx.send(response).await { warn!("receiver dropped") }
                             sleep(Duration::from_millis(args.wait)).await;
                         }
@@ -326,7 +538,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     } else {
         let count = {
-            if args.parallel > 0 {
+            if args.rate.is_some() {
+                args.repeat
+            } else if args.parallel > 0 {
                 args.parallel*args.repeat
             } else {
                 args.repeat
@@ -335,16 +549,100 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         for index in 1..(count+1) {
             if let Some(msg) = rx.recv().await {
                 response.push(msg);
-                eprint!("[{}/{}] running...\r", index, args.parallel*args.repeat);
+                eprint!("[{}/{}] running...\r", index, count);
             }
         }
     }
     debug!("{:?}", response);
 
-    let metrics = statistics(&response);
+    let metrics = statistics(&response, run_start.elapsed());
     debug!("{:?}", metrics);
     println!("{}", serde_json::to_string(&metrics)?);
 
     for x in handle { x.abort() }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile_empty() {
+        assert_eq!(percentile(&[], 50.0), 0);
+        assert_eq!(percentile(&[], 99.9), 0);
+    }
+
+    #[test]
+    fn test_percentile_single_sample() {
+        assert_eq!(percentile(&[42], 0.0), 42);
+        assert_eq!(percentile(&[42], 50.0), 42);
+        assert_eq!(percentile(&[42], 99.9), 42);
+    }
+
+    #[test]
+    fn test_percentile_nearest_rank() {
+        let sorted = (1..=10).collect::<Vec<u64>>();
+        assert_eq!(percentile(&sorted, 50.0), 5);
+        assert_eq!(percentile(&sorted, 90.0), 9);
+        assert_eq!(percentile(&sorted, 100.0), 10);
+    }
+
+    #[test]
+    fn test_phase_stat_single_sample() {
+        let stat = phase_stat(vec![7]);
+        assert_eq!(stat.mean_us, 7);
+        assert_eq!(stat.min_us, 7);
+        assert_eq!(stat.max_us, 7);
+        assert_eq!(stat.p50_us, 7);
+        assert_eq!(stat.p99_us, 7);
+    }
+
+    #[test]
+    fn test_parse_curl_timing_real_http11_output() {
+        // curl 7.88.1で実測した`-w`出力(TLSなし、HTTP/1.1)。
+        // `%{http_version}`はHTTP/1.0・1.1を区別せず常に"1"を返す。
+        let stdout = "200 0.000026 0.001582 0.000000 0.003144 0.003273 1\n";
+        let (status_code, phase, http_version) = parse_curl_timing(stdout);
+
+        assert_eq!(status_code, "200");
+        assert_eq!(http_version.as_deref(), Some("1"));
+        let phase = phase.unwrap();
+        assert!(phase.tls.is_none());
+        assert!(phase.dns.is_some());
+        assert!(phase.connect.is_some());
+        assert!(phase.ttfb.is_some());
+    }
+
+    #[test]
+    fn test_parse_curl_timing_no_ssl_version_field() {
+        // `%{ssl_version}`はcurlに存在しない書き込み変数なので出力に含まれない。
+        // 末尾に余計なトークンが無い前提でパースできることを確認する。
+        let stdout = "200 0.000100 0.000200 0.000300 0.000400 0.000500 2\n";
+        let (_, _, http_version) = parse_curl_timing(stdout);
+        assert_eq!(http_version.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_validate_rate_accepts_none_and_positive() {
+        assert!(validate_rate(None).is_ok());
+        assert!(validate_rate(Some(0.1)).is_ok());
+        assert!(validate_rate(Some(1000.0)).is_ok());
+    }
+
+    #[test]
+    fn test_validate_rate_rejects_zero_negative_and_nan() {
+        assert!(validate_rate(Some(0.0)).is_err());
+        assert!(validate_rate(Some(-1.0)).is_err());
+        assert!(validate_rate(Some(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn test_statistics_empty_response() {
+        let metrics = statistics(&[], Duration::from_secs(1));
+        assert_eq!(metrics.total_requests, 0);
+        assert_eq!(metrics.min_time_us, 0);
+        assert_eq!(metrics.max_time_us, 0);
+        assert_eq!(metrics.p50_time_us, 0);
+    }
+}
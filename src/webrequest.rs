@@ -1,6 +1,34 @@
+use std::net::{IpAddr, SocketAddr};
 use clap::{Parser, ValueEnum, CommandFactory};
 use clap::error::ErrorKind;
-use reqwest::{Client, Error, Method, Request, Response, Url, Version};
+use reqwest::{Client, Method, Request, Url, Version};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::time::Instant;
+use crate::PhaseTimings;
+
+// 呼び出し元はstatus/http_versionしか使わないため、reqwest::Responseとraw
+// unixソケット経路の両方をこの共通の形へ落とし込む。
+#[derive(Debug)]
+pub struct WebResponse {
+    pub status_code: String,
+    pub http_version: String,
+}
+
+// curlの`%{http_version}`は1.0/1.1を区別せず常に"1"を返すため、builtin経路も
+// それに揃えてHTTP/1.0・1.1をどちらも"1"とする。builtin/curlどちらの経路でも
+// Metricsの集計キーが一致するようにする(HTTP/0.9はcurlの`%{http_version}`に
+// 表記がないためそのまま"0.9"とする)。
+fn http_version_label(version: Version) -> String {
+    match version {
+        Version::HTTP_09 => "0.9".to_string(),
+        Version::HTTP_10 => "1".to_string(),
+        Version::HTTP_11 => "1".to_string(),
+        Version::HTTP_2 => "2".to_string(),
+        Version::HTTP_3 => "3".to_string(),
+        other => format!("{:?}", other),
+    }
+}
 
 #[derive(Parser, Debug)]
 pub struct Args {
@@ -54,6 +82,24 @@ pub struct Args {
 
     #[arg(long = "tls-max", value_name = "VERSION", help = "Set maximum allowed TLS version")]
     r#tls_max: Option<String>,
+
+    #[arg(long = "compressed", default_value_t = false, help = "Request compressed response and automatically decompress")]
+    r#compressed: bool,
+
+    #[arg(long = "tls-backend", value_enum, default_value_t = TlsBackend::Native, help = "Select TLS backend to use")]
+    r#tls_backend: TlsBackend,
+
+    #[arg(long = "unix-socket", value_name = "path", help = "Connect through this Unix domain socket, using the URL's Host header")]
+    r#unix_socket: Option<String>,
+
+    #[arg(long = "resolve", value_name = "host:port:addr", help = "Provide a custom address for a specific host and port pair")]
+    r#resolve: Vec<String>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum TlsBackend {
+    Native,
+    Rustls,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
@@ -69,10 +115,27 @@ pub enum HttpMethod {
     Patch,
 }
 
+// "host:port:addr"形式。IPv6アドレスも末尾にそのまま残るようsplitn(3, ':')で区切る。
+fn parse_resolve_entry(row: &str) -> Option<(String, u16, IpAddr)> {
+    let parts = row.splitn(3, ':').collect::<Vec<&str>>();
+    match parts[..] {
+        [host, port, addr] => {
+            let port = port.parse::<u16>().ok()?;
+            let addr = addr.parse::<IpAddr>().ok()?;
+            Some((host.to_string(), port, addr))
+        }
+        _ => None,
+    }
+}
+
 #[derive(Debug)]
 pub struct WebClient {
     client: Client,
     request: Request,
+    unix_socket: Option<String>,
+    // `--resolve host:port:addr`でピン留めされた(host, port)の一覧。
+    // DNSプローブはこの一覧に載っているhostをスキップする(send()参照)。
+    resolved_hosts: Vec<(String, u16)>,
 }
 
 impl WebClient {
@@ -90,8 +153,12 @@ impl WebClient {
             Url::parse(&url_str)?
         };
 
+        let mut resolved_hosts = Vec::new();
+
         let client = {
-            let mut c = Client::builder();
+            let mut c = Client::builder()
+              // 同じ実行内の繰り返し呼び出しでセッションCookieを引き継ぐ。
+              .cookie_store(true);
 
             if let Some(useragent) = arg.user_agent { c = c.user_agent(useragent) }
 
@@ -99,6 +166,20 @@ impl WebClient {
 
             if arg.http09 { c = c.http09_responses() }
 
+            if arg.compressed { c = c.gzip(true).brotli(true) }
+
+            c = match arg.tls_backend {
+                TlsBackend::Native => c.use_native_tls(),
+                TlsBackend::Rustls => c.use_rustls_tls(),
+            };
+
+            for row in &arg.resolve {
+                if let Some((host, port, addr)) = parse_resolve_entry(row) {
+                    c = c.resolve(&host, SocketAddr::new(addr, port));
+                    resolved_hosts.push((host, port));
+                }
+            }
+
             if arg.tlsv1 || arg.tlsv10 { c = c.min_tls_version(reqwest::tls::Version::TLS_1_0) }
             if arg.tlsv11 { c = c.min_tls_version(reqwest::tls::Version::TLS_1_1) }
             if arg.tlsv12 { c = c.min_tls_version(reqwest::tls::Version::TLS_1_2) }
@@ -155,15 +236,132 @@ impl WebClient {
         Ok(Self {
             client,
             request,
+            unix_socket: arg.unix_socket,
+            resolved_hosts,
         })
     }
 
-    pub async fn send(&self) -> Result<Response, Error> {
+    // reqwestはreqwest単体では接続フェーズ単位のフックを公開していないため、
+    // connect/tlsは実際に使われた接続から計測する術がなく計測対象から外す
+    // (以前はTCP接続を別途張って計測していたが、実リクエストとは別の接続に
+    // なってしまい、対象サーバへの接続数を二重にしたうえ、コネクションプール
+    // で接続が使い回される場合に実態と異なる値を報告していたため廃止した)。
+    // DNSは対象サーバに負荷をかけない操作なので送信前に単独でプローブする。
+    // ただし`--resolve`でホストをピン留めしている場合は名前解決自体が行われ
+    // ないため、プローブもスキップしてNone(計測対象外)として扱う。
+    // TTFBは`execute`がレスポンスヘッダ受信時点で完了する性質を利用し、その
+    // まま計測する。
+    pub async fn send(&self) -> anyhow::Result<(WebResponse, PhaseTimings)> {
+        let total_start = Instant::now();
+
+        if let Some(path) = &self.unix_socket {
+            let ttfb_start = Instant::now();
+            let response = send_over_unix_socket(path, &self.request).await?;
+            let ttfb = Some(ttfb_start.elapsed());
+
+            let timings = PhaseTimings {
+                dns: None,
+                connect: None,
+                tls: None,
+                ttfb,
+                total: Some(total_start.elapsed()),
+            };
+
+            return Ok((response, timings));
+        }
+
+        let host = self.request.url().host_str().map(|x| x.to_string());
+        let port = self.request.url().port_or_known_default();
+
+        let pinned = match (&host, port) {
+            (Some(host), Some(port)) => self.resolved_hosts.iter().any(|(h, p)| h == host && *p == port),
+            _ => false,
+        };
+
+        let dns = if pinned {
+            None
+        } else {
+            match (&host, port) {
+                (Some(host), Some(port)) => {
+                    let dns_start = Instant::now();
+                    let _ = tokio::net::lookup_host((host.as_str(), port)).await;
+                    Some(dns_start.elapsed())
+                }
+                _ => None,
+            }
+        };
+
+        let ttfb_start = Instant::now();
         let r = self.request.try_clone().unwrap();
-        self.client.execute(r).await
+        let response = self.client.execute(r).await?;
+        let ttfb = Some(ttfb_start.elapsed());
+
+        let timings = PhaseTimings {
+            dns,
+            connect: None,
+            tls: None,
+            ttfb,
+            total: Some(total_start.elapsed()),
+        };
+
+        let response = WebResponse {
+            status_code: response.status().to_string(),
+            http_version: http_version_label(response.version()),
+        };
+
+        Ok((response, timings))
     }
 }
 
+// reqwestはUnixドメインソケット越しの接続を公開APIとして持たないため、curlの
+// `--unix-socket`と同様にソケット上で生のHTTP/1.1リクエストを組み立てて送る。
+// URLのHostヘッダはそのまま使うことで、ソケットの先にいる複数ホストのサーバにも
+// 対応できるようにする。
+fn build_request_head(request: &Request, body_len: usize) -> String {
+    let host = request.url().host_str().unwrap_or("localhost");
+    let mut path_and_query = request.url().path().to_string();
+    if let Some(query) = request.url().query() {
+        path_and_query.push('?');
+        path_and_query.push_str(query);
+    }
+
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", request.method(), path_and_query, host);
+    for (name, value) in request.headers() {
+        head.push_str(&format!("{}: {}\r\n", name, value.to_str().unwrap_or("")));
+    }
+    if body_len > 0 {
+        head.push_str(&format!("Content-Length: {}\r\n", body_len));
+    }
+    head.push_str("\r\n");
+
+    head
+}
+
+async fn send_over_unix_socket(path: &str, request: &Request) -> anyhow::Result<WebResponse> {
+    let body = request.body().and_then(|b| b.as_bytes()).unwrap_or(&[]);
+    let head = build_request_head(request, body.len());
+
+    let mut stream = UnixStream::connect(path).await?;
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await?;
+
+    let text = String::from_utf8_lossy(&buf);
+    let status_line = text.lines().next().ok_or_else(|| anyhow::anyhow!("empty response from unix socket {path}"))?;
+
+    let mut field = status_line.splitn(3, ' ');
+    // http_version_labelと同じく、HTTP/1.0・1.1はどちらも"1"とする。
+    let http_version = match field.next().unwrap_or("HTTP/1.1").trim_start_matches("HTTP/") {
+        "1.0" | "1.1" => "1".to_string(),
+        other => other.to_string(),
+    };
+    let status_code = field.next().unwrap_or("0").to_string();
+
+    Ok(WebResponse { status_code, http_version })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -206,4 +404,96 @@ mod tests {
 
         let _client = WebClient::build(&arg);
     }
+
+    #[test]
+    fn test_args_compressed_and_tls_backend() {
+        let args = Args::try_parse_from(
+            [
+                "cmd",
+                "localhost",
+                "--compressed",
+                "--tls-backend", "rustls",
+            ]
+        );
+
+        assert!(args.is_ok());
+        let args = args.unwrap();
+        assert!(args.compressed);
+        assert_eq!(args.tls_backend, TlsBackend::Rustls);
+    }
+
+    #[test]
+    fn test_build_with_compressed_and_tls_backend() {
+        let arg = vec![
+                "cmd",
+                "localhost",
+                "--compressed",
+                "--tls-backend", "rustls",
+            ].into_iter()
+          .map(String::from)
+          .collect::<Vec<String>>();
+
+        let client = WebClient::build(&arg);
+
+        assert!(client.is_ok());
+    }
+
+    #[test]
+    fn test_parse_resolve_entry() {
+        let (host, port, addr) = parse_resolve_entry("example.com:443:127.0.0.1").unwrap();
+        assert_eq!(host, "example.com");
+        assert_eq!(port, 443);
+        assert_eq!(addr, "127.0.0.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_parse_resolve_entry_ipv6() {
+        let (host, port, addr) = parse_resolve_entry("example.com:443:::1").unwrap();
+        assert_eq!(host, "example.com");
+        assert_eq!(port, 443);
+        assert_eq!(addr, "::1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_parse_resolve_entry_malformed() {
+        assert!(parse_resolve_entry("example.com").is_none());
+        assert!(parse_resolve_entry("example.com:not-a-port:127.0.0.1").is_none());
+        assert!(parse_resolve_entry("example.com:443:not-an-addr").is_none());
+    }
+
+    #[test]
+    fn test_build_request_head() {
+        let request = Client::new()
+          .get("http://example.com/path?query=1")
+          .build()
+          .unwrap();
+
+        let head = build_request_head(&request, 0);
+
+        assert!(head.starts_with("GET /path?query=1 HTTP/1.1\r\n"));
+        assert!(head.contains("Host: example.com\r\n"));
+        assert!(head.ends_with("\r\n\r\n"));
+        assert!(!head.contains("Content-Length"));
+    }
+
+    #[test]
+    fn test_build_request_head_with_body() {
+        let request = Client::new()
+          .post("http://example.com/")
+          .build()
+          .unwrap();
+
+        let head = build_request_head(&request, 11);
+
+        assert!(head.contains("Content-Length: 11\r\n"));
+    }
+
+    #[test]
+    fn test_http_version_label_collapses_1_0_and_1_1() {
+        // curlの`%{http_version}`は1.0/1.1を区別せず常に"1"を返すため、それに揃える。
+        assert_eq!(http_version_label(Version::HTTP_10), "1");
+        assert_eq!(http_version_label(Version::HTTP_11), "1");
+        assert_eq!(http_version_label(Version::HTTP_2), "2");
+        assert_eq!(http_version_label(Version::HTTP_3), "3");
+    }
 }